@@ -1,3 +1,4 @@
+use async_trait::async_trait;
 use sodiumoxide::{
     crypto::aead::xchacha20poly1305_ietf::{gen_nonce, open, seal, Key, Nonce},
     hex,
@@ -9,8 +10,128 @@ use uuid::Uuid;
 
 use crate::db;
 
+/// A ciphertext/nonce pair for a user's stored browser storage state. Both fields are
+/// hex-encoded, as they're already sealed by the AEAD layer above the backend.
+#[derive(Debug, Clone)]
+pub struct EncryptedStorageState {
+    pub ciphertext_hex: String,
+    pub nonce_hex: String,
+}
+
+/// Where encrypted storage states are persisted. Implementations only ever see
+/// ciphertext: the XChaCha20-Poly1305 seal/open happens in `insert_storage_state` and
+/// `get_storage_state`, above this trait, so neither backend needs to know about
+/// encryption.
+#[async_trait]
+pub trait StorageStateBackend: Send + Sync {
+    async fn put(&self, user_id: &Uuid, state: &EncryptedStorageState) -> Result<()>;
+
+    async fn get(&self, user_id: &Uuid) -> Result<Option<EncryptedStorageState>>;
+}
+
+/// The original backend: one row per user in `user_storage_states`.
+pub struct PostgresStorageStateBackend {
+    pool: PgPool,
+}
+
+impl PostgresStorageStateBackend {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl StorageStateBackend for PostgresStorageStateBackend {
+    async fn put(&self, user_id: &Uuid, state: &EncryptedStorageState) -> Result<()> {
+        db::user_storage_states::insert_user_storage_state(
+            &self.pool,
+            user_id,
+            &vec![state.ciphertext_hex.clone()],
+            &vec![state.nonce_hex.clone()],
+        )
+        .await
+    }
+
+    async fn get(&self, user_id: &Uuid) -> Result<Option<EncryptedStorageState>> {
+        let states = db::user_storage_states::get_user_storage_state(&self.pool, user_id).await?;
+
+        Ok(states.first().map(|s| EncryptedStorageState {
+            ciphertext_hex: s.cookies.clone(),
+            nonce_hex: s.nonce.clone(),
+        }))
+    }
+}
+
+/// Object-store backend for large storage states that don't belong in a Postgres row.
+/// Objects are keyed as `<key_prefix>/<user_id>`.
+pub struct S3StorageStateBackend {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+    key_prefix: String,
+}
+
+impl S3StorageStateBackend {
+    pub fn new(client: aws_sdk_s3::Client, bucket: String, key_prefix: String) -> Self {
+        Self {
+            client,
+            bucket,
+            key_prefix,
+        }
+    }
+
+    fn object_key(&self, user_id: &Uuid) -> String {
+        format!("{}/{}", self.key_prefix.trim_end_matches('/'), user_id)
+    }
+}
+
+#[async_trait]
+impl StorageStateBackend for S3StorageStateBackend {
+    async fn put(&self, user_id: &Uuid, state: &EncryptedStorageState) -> Result<()> {
+        // Nonce is short and safe to carry as object metadata alongside the ciphertext body.
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(self.object_key(user_id))
+            .metadata("nonce", &state.nonce_hex)
+            .body(state.ciphertext_hex.clone().into_bytes().into())
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, user_id: &Uuid) -> Result<Option<EncryptedStorageState>> {
+        let object = match self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.object_key(user_id))
+            .send()
+            .await
+        {
+            Ok(object) => object,
+            Err(aws_sdk_s3::error::SdkError::ServiceError(e)) if e.err().is_no_such_key() => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let nonce_hex = object
+            .metadata()
+            .and_then(|m| m.get("nonce"))
+            .ok_or(anyhow::anyhow!("Stored object is missing its nonce metadata"))?
+            .clone();
+        let ciphertext_hex = String::from_utf8(object.body.collect().await?.to_vec())?;
+
+        Ok(Some(EncryptedStorageState {
+            ciphertext_hex,
+            nonce_hex,
+        }))
+    }
+}
+
 pub async fn insert_storage_state(
-    pool: &PgPool,
+    backend: &dyn StorageStateBackend,
     user_id: &Uuid,
     storage_state: &String,
 ) -> Result<()> {
@@ -20,33 +141,34 @@ pub async fn insert_storage_state(
     let nonce = gen_nonce();
     let encrypted = seal(&storage_state.as_bytes(), None, &nonce, &key);
 
-    db::user_storage_states::insert_user_storage_state(
-        pool,
-        user_id,
-        &vec![hex::encode(encrypted)],
-        &vec![hex::encode(nonce)],
-    )
-    .await?;
+    backend
+        .put(
+            user_id,
+            &EncryptedStorageState {
+                ciphertext_hex: hex::encode(encrypted),
+                nonce_hex: hex::encode(nonce),
+            },
+        )
+        .await?;
 
     Ok(())
 }
 
-pub async fn get_storage_state(pool: &PgPool, user_id: &Uuid) -> Result<Option<String>> {
-    let states = db::user_storage_states::get_user_storage_state(pool, user_id).await?;
-
-    if states.is_empty() {
+pub async fn get_storage_state(
+    backend: &dyn StorageStateBackend,
+    user_id: &Uuid,
+) -> Result<Option<String>> {
+    let Some(encrypted_state) = backend.get(user_id).await? else {
         return Ok(None);
-    }
-
-    let encrypted_state = states.first().unwrap();
+    };
 
     let key_hex = std::env::var("AEAD_SECRET_KEY").unwrap();
     let key = Key::from_slice(hex::decode(key_hex).unwrap().as_slice()).unwrap();
 
-    let encrypted = hex::decode(encrypted_state.cookies.clone()).or(Err(anyhow::anyhow!(
+    let encrypted = hex::decode(encrypted_state.ciphertext_hex).or(Err(anyhow::anyhow!(
         "Failed to decode hex value for cookie",
     )))?;
-    let nonce_bytes = hex::decode(encrypted_state.nonce.clone()).or(Err(anyhow::anyhow!(
+    let nonce_bytes = hex::decode(encrypted_state.nonce_hex).or(Err(anyhow::anyhow!(
         "Failed to decode hex nonce for cookie",
     )))?;
 
@@ -58,3 +180,27 @@ pub async fn get_storage_state(pool: &PgPool, user_id: &Uuid) -> Result<Option<S
 
     Ok(Some(String::from_utf8(decrypted).unwrap()))
 }
+
+/// Selects which `StorageStateBackend` to use, read from config at startup.
+pub enum StorageStateBackendConfig {
+    Postgres,
+    S3 { bucket: String, key_prefix: String },
+}
+
+pub async fn build_storage_state_backend(
+    config: StorageStateBackendConfig,
+    pool: PgPool,
+) -> Result<Box<dyn StorageStateBackend>> {
+    match config {
+        StorageStateBackendConfig::Postgres => {
+            Ok(Box::new(PostgresStorageStateBackend::new(pool)))
+        }
+        StorageStateBackendConfig::S3 { bucket, key_prefix } => {
+            let aws_config = aws_config::load_from_env().await;
+            let client = aws_sdk_s3::Client::new(&aws_config);
+            Ok(Box::new(S3StorageStateBackend::new(
+                client, bucket, key_prefix,
+            )))
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A single turn in a chat conversation, normalized across providers.
+///
+/// When a system message is present, it must be the first element of the
+/// message list passed to [`super::runner::LanguageModelRunner::chat_completion`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "role", rename_all = "snake_case")]
+pub enum ChatMessage {
+    System { content: String },
+    User { content: String },
+    Assistant { content: String },
+    /// A model-requested invocation of a tool. Emitted by the provider inside a
+    /// [`ChatCompletion`] and echoed back into the message history so the model
+    /// can see which calls it made.
+    ToolCall {
+        id: String,
+        name: String,
+        arguments: Value,
+    },
+    /// The result of executing a `ToolCall`, fed back to the model so it can
+    /// continue the conversation.
+    ToolResult {
+        tool_call_id: String,
+        content: String,
+    },
+}
+
+/// A tool the model is allowed to call, normalized across providers.
+///
+/// Translated by each provider implementation into its native wire format,
+/// e.g. OpenAI `tools`, Anthropic/Bedrock `tools`, or Gemini `functionDeclarations`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    /// JSON schema describing the tool's parameters.
+    pub parameters: Value,
+}
+
+/// A tool invocation requested by the model, normalized across providers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: Value,
+}
+
+impl From<&ToolCall> for ChatMessage {
+    fn from(tool_call: &ToolCall) -> Self {
+        ChatMessage::ToolCall {
+            id: tool_call.id.clone(),
+            name: tool_call.name.clone(),
+            arguments: tool_call.arguments.clone(),
+        }
+    }
+}
+
+/// Result of executing a [`ToolCall`], to be appended to the message history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolResult {
+    pub tool_call_id: String,
+    pub content: String,
+}
+
+impl From<ToolResult> for ChatMessage {
+    fn from(tool_result: ToolResult) -> Self {
+        ChatMessage::ToolResult {
+            tool_call_id: tool_result.tool_call_id,
+            content: tool_result.content,
+        }
+    }
+}
+
+/// The outcome of a single round-trip to a provider.
+///
+/// `tool_calls` is non-empty when the provider asked to invoke tools instead
+/// of returning a final answer; `text` is the final assistant message once
+/// the model is done calling tools.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ChatCompletion {
+    pub text: String,
+    #[serde(default)]
+    pub tool_calls: Vec<ToolCall>,
+}
+
+impl ChatCompletion {
+    pub fn is_tool_call(&self) -> bool {
+        !self.tool_calls.is_empty()
+    }
+}
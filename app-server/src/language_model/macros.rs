@@ -0,0 +1,57 @@
+use anyhow::Result;
+use enum_dispatch::enum_dispatch;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Generates the `LanguageModelProviderName`/`LanguageModelProvider` enums plus their
+/// `from_str`, `api_key_name`, and `required_env_vars` impls from a single list of
+/// providers, so those stay in sync. `LanguageModelProviderName::env_prefix` in `runner.rs`
+/// is not generated here and needs its own match arm for each new provider.
+///
+/// Each entry is `(Variant, "wire-name", api_key_env, [extra_required_env_vars], ProviderType)`.
+/// `api_key_env` may be a string literal or a `const &str` (e.g. Bedrock's
+/// `AWS_SECRET_ACCESS_KEY`; its `AWS_ACCESS_KEY_ID` is listed among the extra env vars).
+macro_rules! register_provider {
+    ($(($variant:ident, $name:literal, $api_key_env:expr, [$($extra_env:expr),* $(,)?], $provider_ty:ident)),+ $(,)?) => {
+        #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+        pub enum LanguageModelProviderName {
+            $($variant,)+
+        }
+
+        #[derive(Clone, Debug)]
+        #[enum_dispatch]
+        pub enum LanguageModelProvider {
+            $($variant($provider_ty),)+
+        }
+
+        impl LanguageModelProviderName {
+            pub fn from_str(s: &str) -> Result<Self> {
+                match s {
+                    $($name => Ok(Self::$variant),)+
+                    _ => Err(anyhow::anyhow!("Invalid language model provider: {}", s)),
+                }
+            }
+
+            fn api_key_name(&self) -> &str {
+                match self {
+                    $(Self::$variant => $api_key_env,)+
+                }
+            }
+
+            pub fn required_env_vars(&self) -> HashSet<String> {
+                let mut env_vars = HashSet::new();
+                env_vars.insert(self.api_key_name().to_string());
+
+                match self {
+                    $(Self::$variant => {
+                        $(env_vars.insert($extra_env.to_string());)*
+                    })+
+                }
+
+                env_vars
+            }
+        }
+    };
+}
+
+pub(super) use register_provider;
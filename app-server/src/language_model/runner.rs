@@ -1,4 +1,5 @@
 use anyhow::{Context, Result};
+use async_trait::async_trait;
 use enum_dispatch::enum_dispatch;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
@@ -13,36 +14,37 @@ use uuid::Uuid;
 use crate::{cache::Cache, db::DB, pipeline::nodes::StreamChunk, traces::spans::InputTokens};
 
 use super::{
-    chat_message::ChatCompletion,
+    chat_message::{ChatCompletion, ToolCall, ToolDefinition, ToolResult},
     providers::{
         anthropic_bedrock::{AWS_ACCESS_KEY_ID, AWS_REGION, AWS_SECRET_ACCESS_KEY},
         openai_azure::{OPENAI_AZURE_DEPLOYMENT_NAME, OPENAI_AZURE_RESOURCE_ID},
         utils::get_provider,
     },
-    Anthropic, AnthropicBedrock, ChatMessage, Gemini, Groq, Mistral, OpenAI, OpenAIAzure,
+    macros::register_provider, Anthropic, AnthropicBedrock, ChatMessage, Gemini, Groq, Mistral,
+    OpenAI, OpenAIAzure,
 };
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
-pub enum LanguageModelProviderName {
-    Anthropic,
-    Mistral,
-    OpenAI,
-    OpenAIAzure,
-    Gemini,
-    Groq,
-    Bedrock,
+/// Default cap on the number of tool-call round-trips `LanguageModelRunner::chat_completion`
+/// will make before giving up, to guard against a model that never stops calling tools.
+pub const DEFAULT_MAX_TOOL_ITERATIONS: u32 = 5;
+
+/// Executes a tool call requested by the model and returns its result.
+///
+/// Implemented by the caller (e.g. the pipeline node running the agent loop), since
+/// the runner itself has no knowledge of what tools are available or how to run them.
+#[async_trait]
+pub trait ToolExecutor: Send + Sync {
+    async fn execute_tool(&self, tool_call: &ToolCall) -> Result<ToolResult>;
 }
 
-#[derive(Clone, Debug)]
-#[enum_dispatch]
-pub enum LanguageModelProvider {
-    Anthropic(Anthropic),
-    Gemini(Gemini),
-    Groq(Groq),
-    Mistral(Mistral),
-    OpenAI(OpenAI),
-    OpenAIAzure(OpenAIAzure),
-    Bedrock(AnthropicBedrock),
+register_provider! {
+    (Anthropic, "anthropic", "ANTHROPIC_API_KEY", [], Anthropic),
+    (Mistral, "mistral", "MISTRAL_API_KEY", [], Mistral),
+    (OpenAI, "openai", "OPENAI_API_KEY", [], OpenAI),
+    (OpenAIAzure, "openai-azure", "AZURE_API_KEY", [OPENAI_AZURE_RESOURCE_ID, OPENAI_AZURE_DEPLOYMENT_NAME], OpenAIAzure),
+    (Gemini, "gemini", "GEMINI_API_KEY", [], Gemini),
+    (Groq, "groq", "GROQ_API_KEY", [], Groq),
+    (Bedrock, "bedrock", AWS_SECRET_ACCESS_KEY, [AWS_REGION, AWS_ACCESS_KEY_ID], AnthropicBedrock),
 }
 
 #[enum_dispatch(LanguageModelProvider)]
@@ -51,8 +53,8 @@ pub trait ExecuteChatCompletion {
         &self,
         model: &str,
         provider_name: LanguageModelProviderName,
-        messages: &Vec<ChatMessage>,
-        params: &Value,
+        request: &ProviderRequest,
+        transport: &ProviderTransportConfig,
         env: &HashMap<String, String>,
         tx: Option<Sender<StreamChunk>>,
         node_info: &NodeInfo,
@@ -61,27 +63,43 @@ pub trait ExecuteChatCompletion {
     ) -> Result<ChatCompletion>;
 }
 
+/// What to send a provider for a single chat-completion round-trip.
+#[derive(Debug, Clone)]
+pub enum ProviderRequest {
+    /// The normal path: our `ChatMessage`/`ToolDefinition` types, translated into the
+    /// provider's wire format by its `ExecuteChatCompletion` implementation.
+    Normalized {
+        messages: Vec<ChatMessage>,
+        params: Value,
+        tools: Option<Vec<ToolDefinition>>,
+    },
+    /// The provider's native request body, forwarded verbatim aside from injecting auth
+    /// and streaming wiring. The response is still parsed back into a `ChatCompletion`
+    /// for cost/trace accounting. Use this to reach a provider feature that isn't
+    /// modeled by our normalized types yet, without waiting for us to model it.
+    Raw(Value),
+}
+
 #[enum_dispatch(LanguageModelProvider)]
 pub trait EstimateCost {
     fn db_provider_name(&self) -> &str;
 
+    /// `input_tokens` carries the regular/cache-write/cache-read breakdown reported by
+    /// the provider's usage block, so prompt-caching discounts (Anthropic, Bedrock,
+    /// OpenAI cached input) are priced correctly instead of billed as regular tokens.
     async fn estimate_input_cost(
         &self,
         db: Arc<DB>,
         cache: Arc<Cache>,
         model: &str,
-        input_tokens: u32,
+        input_tokens: InputTokens,
     ) -> Option<f64> {
         super::costs::estimate_input_cost(
             db.clone(),
             cache.clone(),
             self.db_provider_name(),
             model,
-            InputTokens {
-                regular_input_tokens: input_tokens as i64,
-                cache_write_tokens: 0,
-                cache_read_tokens: 0,
-            },
+            input_tokens,
         )
         .await
     }
@@ -108,7 +126,7 @@ pub trait EstimateCost {
         db: Arc<DB>,
         cache: Arc<Cache>,
         model: &str,
-        input_tokens: u32,
+        input_tokens: InputTokens,
         output_tokens: u32,
     ) -> Option<f64> {
         let input_cost = self
@@ -131,19 +149,6 @@ pub trait EstimateCost {
 }
 
 impl LanguageModelProviderName {
-    pub fn from_str(s: &str) -> Result<Self> {
-        match s {
-            "anthropic" => Ok(Self::Anthropic),
-            "mistral" => Ok(Self::Mistral),
-            "openai" => Ok(Self::OpenAI),
-            "openai-azure" => Ok(Self::OpenAIAzure),
-            "gemini" => Ok(Self::Gemini),
-            "groq" => Ok(Self::Groq),
-            "bedrock" => Ok(Self::Bedrock),
-            _ => Err(anyhow::anyhow!("Invalid language model provider: {}", s)),
-        }
-    }
-
     pub fn api_key(&self, env: &HashMap<String, String>) -> Result<String> {
         let name = self.api_key_name();
         env.get(name)
@@ -151,31 +156,36 @@ impl LanguageModelProviderName {
             .ok_or(anyhow::anyhow!("Env variables don't contain: {}", name))
     }
 
-    fn api_key_name(&self) -> &str {
+    /// Env var prefix used to namespace the optional transport tuning vars read by
+    /// [`Self::transport_config`], e.g. `"OPENAI"` for `OPENAI_PROXY`.
+    fn env_prefix(&self) -> &str {
         match self {
-            LanguageModelProviderName::Anthropic => "ANTHROPIC_API_KEY",
-            LanguageModelProviderName::Mistral => "MISTRAL_API_KEY",
-            LanguageModelProviderName::OpenAI => "OPENAI_API_KEY",
-            LanguageModelProviderName::OpenAIAzure => "AZURE_API_KEY",
-            LanguageModelProviderName::Gemini => "GEMINI_API_KEY",
-            LanguageModelProviderName::Groq => "GROQ_API_KEY",
-            LanguageModelProviderName::Bedrock => AWS_SECRET_ACCESS_KEY,
+            LanguageModelProviderName::Anthropic => "ANTHROPIC",
+            LanguageModelProviderName::Mistral => "MISTRAL",
+            LanguageModelProviderName::OpenAI => "OPENAI",
+            LanguageModelProviderName::OpenAIAzure => "AZURE",
+            LanguageModelProviderName::Gemini => "GEMINI",
+            LanguageModelProviderName::Groq => "GROQ",
+            LanguageModelProviderName::Bedrock => "BEDROCK",
         }
     }
 
-    pub fn required_env_vars(&self) -> HashSet<String> {
-        let mut env_vars = HashSet::new();
-        env_vars.insert(self.api_key_name().to_string());
+    /// Reads this provider's optional HTTP transport tuning from `env`. Unlike
+    /// [`Self::required_env_vars`], none of these are mandatory, so a deployment that
+    /// doesn't set them gets the provider's default client unchanged.
+    ///
+    /// Recognized vars: `<PREFIX>_PROXY`, `<PREFIX>_CONNECT_TIMEOUT_SECONDS`,
+    /// `<PREFIX>_API_BASE`, e.g. `OPENAI_PROXY` or `BEDROCK_CONNECT_TIMEOUT_SECONDS`.
+    pub fn transport_config(&self, env: &HashMap<String, String>) -> ProviderTransportConfig {
+        let prefix = self.env_prefix();
 
-        if matches!(self, Self::Bedrock) {
-            env_vars.insert(AWS_REGION.to_string());
-            env_vars.insert(AWS_ACCESS_KEY_ID.to_string());
-        } else if matches!(self, Self::OpenAIAzure) {
-            env_vars.insert(OPENAI_AZURE_RESOURCE_ID.to_string());
-            env_vars.insert(OPENAI_AZURE_DEPLOYMENT_NAME.to_string());
+        ProviderTransportConfig {
+            proxy: env.get(&format!("{prefix}_PROXY")).cloned(),
+            connect_timeout_seconds: env
+                .get(&format!("{prefix}_CONNECT_TIMEOUT_SECONDS"))
+                .and_then(|s| s.parse().ok()),
+            api_base: env.get(&format!("{prefix}_API_BASE")).cloned(),
         }
-
-        env_vars
     }
 }
 
@@ -189,6 +199,23 @@ impl LanguageModelRunner {
         Self { models }
     }
 
+    /// Resolves `model` (in the format of "provider:model_name") to its executor, its
+    /// bare model name (with the provider prefix stripped), and provider name.
+    fn resolve_executor(
+        &self,
+        model: &str,
+    ) -> Result<(&LanguageModelProvider, String, LanguageModelProviderName)> {
+        let provider = get_provider(model).context("Invalid model format")?;
+        let model_name = model.split(":").skip(1).join(":");
+        if model_name.is_empty() {
+            return Err(anyhow::anyhow!("Invalid model format"));
+        }
+        let provider_name = LanguageModelProviderName::from_str(provider)?;
+        let executor = self.models.get(&provider_name).unwrap();
+
+        Ok((executor, model_name, provider_name))
+    }
+
     /// Completes the chat by calling model's executor
     ///
     /// # Arguments
@@ -200,31 +227,108 @@ impl LanguageModelRunner {
     /// * messages - list of messages in the chat.
     ///     If system message is passed, then it must be put as first message!
     ///     Next, alternating user and assistant messages are passed starting from user message.
+    ///
+    /// * tools - tool definitions the model may call. When the provider responds with tool
+    ///     calls instead of a final message, they are executed via `tool_executor` and fed
+    ///     back to the model, repeating until a final message is returned or
+    ///     `max_tool_iterations` round-trips are exhausted.
     pub async fn chat_completion(
         &self,
         model: &str,
         messages: &Vec<ChatMessage>,
         params: &Value,
+        tools: Option<Vec<ToolDefinition>>,
+        tool_executor: Option<Arc<dyn ToolExecutor>>,
+        max_tool_iterations: Option<u32>,
         env: &HashMap<String, String>,
         tx: Option<Sender<StreamChunk>>,
         node_info: &NodeInfo,
         db: Arc<DB>,
         cache: Arc<Cache>,
     ) -> Result<ChatCompletion> {
-        let provider = get_provider(model).context("Invalid model format")?;
-        let model_name = model.split(":").skip(1).join(":");
-        if model_name.is_empty() {
-            return Err(anyhow::anyhow!("Invalid model format"));
+        let (executor, model_name, provider_name) = self.resolve_executor(model)?;
+        let transport = provider_name.transport_config(env);
+        let max_tool_iterations = max_tool_iterations.unwrap_or(DEFAULT_MAX_TOOL_ITERATIONS);
+
+        let mut conversation = messages.clone();
+
+        for iteration in 0..max_tool_iterations {
+            let request = ProviderRequest::Normalized {
+                messages: conversation.clone(),
+                params: params.clone(),
+                tools: tools.clone(),
+            };
+            let completion = executor
+                .chat_completion(
+                    model_name.as_str(),
+                    provider_name.clone(),
+                    &request,
+                    &transport,
+                    env,
+                    tx.clone(),
+                    node_info,
+                    db.clone(),
+                    cache.clone(),
+                )
+                .await?;
+
+            if !completion.is_tool_call() {
+                return Ok(completion);
+            }
+
+            if iteration + 1 >= max_tool_iterations {
+                break;
+            }
+
+            let Some(tool_executor) = tool_executor.as_ref() else {
+                return Err(anyhow::anyhow!(
+                    "Model requested {} tool call(s) but no tool executor was provided",
+                    completion.tool_calls.len()
+                ));
+            };
+
+            for tool_call in &completion.tool_calls {
+                conversation.push(ChatMessage::from(tool_call));
+                let tool_result = tool_executor.execute_tool(tool_call).await?;
+                conversation.push(ChatMessage::from(tool_result));
+            }
+
+            log::debug!(
+                "Tool-call round-trip {} of {} for model {}",
+                iteration + 1,
+                max_tool_iterations,
+                model
+            );
         }
-        let provider_name = LanguageModelProviderName::from_str(provider)?;
 
-        let executor = self.models.get(&provider_name).unwrap();
+        Err(anyhow::anyhow!(
+            "Exceeded max_tool_iterations ({}) without a final response",
+            max_tool_iterations
+        ))
+    }
+
+    /// Forwards `native_request` to the provider's API verbatim, only injecting auth and
+    /// streaming wiring, instead of building the request from `ChatMessage`s. See
+    /// [`ProviderRequest::Raw`].
+    pub async fn raw_chat_completion(
+        &self,
+        model: &str,
+        native_request: Value,
+        env: &HashMap<String, String>,
+        tx: Option<Sender<StreamChunk>>,
+        node_info: &NodeInfo,
+        db: Arc<DB>,
+        cache: Arc<Cache>,
+    ) -> Result<ChatCompletion> {
+        let (executor, model_name, provider_name) = self.resolve_executor(model)?;
+        let transport = provider_name.transport_config(env);
+
         executor
             .chat_completion(
                 model_name.as_str(),
                 provider_name,
-                messages,
-                params,
+                &ProviderRequest::Raw(native_request),
+                &transport,
                 env,
                 tx,
                 node_info,
@@ -235,6 +339,21 @@ impl LanguageModelRunner {
     }
 }
 
+/// Optional HTTP transport tuning for a provider's client, sourced from env by
+/// [`LanguageModelProviderName::transport_config`] on each call and passed to the
+/// provider's [`ExecuteChatCompletion::chat_completion`]. Lets self-hosted deployments route calls through a corporate proxy,
+/// raise timeouts for slow regions, or point an OpenAI-compatible model at a gateway
+/// URL, all without code changes.
+#[derive(Debug, Clone, Default)]
+pub struct ProviderTransportConfig {
+    /// `http(s)://` or `socks5://` proxy URL for this provider's outbound requests.
+    pub proxy: Option<String>,
+    pub connect_timeout_seconds: Option<u64>,
+    /// Overrides the provider's default API base URL, e.g. to point an
+    /// OpenAI-compatible client at a self-hosted gateway.
+    pub api_base: Option<String>,
+}
+
 /// Information on the node to send along the streaming
 #[derive(Debug, Clone)]
 pub struct NodeInfo {